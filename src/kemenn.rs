@@ -7,9 +7,13 @@
 //
 
 use anyhow::{anyhow, Context, Result};
+use base64;
 use dirs;
+use mime_guess;
 use elb_dev_tools_ng::run_command_or;
 use handlebars::{no_escape, Handlebars};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Envelope, SmtpTransport, Transport as LettreTransport};
 use regex::Regex;
 use std::collections::HashMap;
 use std::env;
@@ -17,14 +21,14 @@ use std::fs;
 use std::fs::File;
 use std::io::{self, BufRead, BufReader, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
 use std::str;
 use structopt::StructOpt;
 
 const DEFAULT_TEMPLATE: &str = r"From: {{emitter}}
 To: {{recipients}}
 Subject: [{{prefix}}] {{project}} {{version}} is available
-bcc: {{emitter}}
+bcc: {{bcc}}
 
 Hi!
 
@@ -83,6 +87,12 @@ struct KemennOpts {
     )]
     loose: bool,
 
+    #[structopt(
+        long = "from-git",
+        help = "Generate changelog from git history using Conventional Commits"
+    )]
+    from_git: bool,
+
     #[structopt(
         short = "o",
         long = "output",
@@ -109,11 +119,164 @@ struct KemennOpts {
     )]
     release: Option<String>,
 
+    #[structopt(
+        short = "a",
+        long = "attach",
+        help = "Attach a file to the announcement",
+        value_name = "PATH",
+        parse(from_os_str)
+    )]
+    attachments: Vec<PathBuf>,
+
+    #[structopt(
+        long = "sign",
+        help = "Sign the announcement with OpenPGP (optionally specifying a key id)",
+        value_name = "KEYID",
+        min_values = 0,
+        max_values = 1
+    )]
+    sign: Option<Vec<String>>,
+
     #[structopt(help = "Repository")]
     repository: PathBuf,
 
     #[structopt(help = "Recipients")]
     recipients: Vec<String>,
+
+    #[structopt(long = "send", help = "Send the announcement instead of writing it out")]
+    send: bool,
+
+    #[structopt(
+        long = "sendmail-cmd",
+        help = "Path to sendmail-compatible binary",
+        value_name = "PATH"
+    )]
+    sendmail_cmd: Option<String>,
+
+    #[structopt(
+        long = "smtp-host",
+        help = "SMTP server host (enables SMTP delivery)",
+        value_name = "HOST"
+    )]
+    smtp_host: Option<String>,
+
+    #[structopt(
+        long = "smtp-port",
+        help = "SMTP server port",
+        value_name = "PORT"
+    )]
+    smtp_port: Option<u16>,
+
+    #[structopt(
+        long = "smtp-user",
+        help = "SMTP username",
+        value_name = "USER"
+    )]
+    smtp_user: Option<String>,
+
+    #[structopt(
+        long = "config",
+        help = "Path to config file",
+        value_name = "PATH",
+        parse(from_os_str)
+    )]
+    config: Option<PathBuf>,
+
+    #[structopt(
+        long = "profile",
+        help = "Name of sender profile to use",
+        value_name = "NAME"
+    )]
+    profile: Option<String>,
+}
+
+const DEFAULT_SENDMAIL_CMD: &str = "sendmail";
+const DEFAULT_SMTP_PORT: u16 = 587;
+
+/// Transport settings embedded in the config file
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct TransportConfig {
+    sendmail_cmd: Option<String>,
+    smtp_host: Option<String>,
+    smtp_port: Option<u16>,
+    smtp_user: Option<String>,
+    smtp_password: Option<String>,
+}
+
+/// Settings for a named sender profile (or the global section)
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct ProfileConfig {
+    emitter: Option<String>,
+    signature: Option<PathBuf>,
+    template: Option<PathBuf>,
+    recipients: Option<Vec<String>>,
+    bcc: Option<Vec<String>>,
+    prefix: Option<String>,
+    transport: Option<TransportConfig>,
+}
+
+impl ProfileConfig {
+    /// Fill the fields missing from `self` with those from `other`
+    fn merge(&self, other: &ProfileConfig) -> ProfileConfig {
+        ProfileConfig {
+            emitter: self.emitter.clone().or_else(|| other.emitter.clone()),
+            signature: self
+                .signature
+                .clone()
+                .or_else(|| other.signature.clone()),
+            template: self.template.clone().or_else(|| other.template.clone()),
+            recipients: self
+                .recipients
+                .clone()
+                .or_else(|| other.recipients.clone()),
+            bcc: self.bcc.clone().or_else(|| other.bcc.clone()),
+            prefix: self.prefix.clone().or_else(|| other.prefix.clone()),
+            transport: self
+                .transport
+                .clone()
+                .or_else(|| other.transport.clone()),
+        }
+    }
+}
+
+/// Sender configuration, holding a global section and named profiles
+#[derive(Debug, Default, serde::Deserialize)]
+struct Config {
+    #[serde(flatten)]
+    global: ProfileConfig,
+    #[serde(default)]
+    profiles: HashMap<String, ProfileConfig>,
+}
+
+impl Config {
+    /// Load a `Config` from a TOML file
+    fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let text = fs::read_to_string(path).context("Failed to read config file")?;
+        let config: Config =
+            toml::from_str(&text).context("Failed to parse config file")?;
+        Ok(config)
+    }
+
+    /// Default location of the config file: `~/.config/kemenn/config.toml`
+    fn default_path() -> Option<PathBuf> {
+        let mut path = dirs::config_dir()?;
+        path.push("kemenn");
+        path.push("config.toml");
+        Some(path)
+    }
+
+    /// Resolve the settings for `name`, falling back to the global section
+    fn resolve(&self, name: Option<&str>) -> Result<ProfileConfig> {
+        match name {
+            Some(name) => match self.profiles.get(name) {
+                Some(profile) => Ok(profile.merge(&self.global)),
+                None => Err(anyhow!("Unknown profile '{}'", name)),
+            },
+            None => Ok(self.global.clone()),
+        }
+    }
 }
 
 /// Represent information about a release
@@ -173,6 +336,127 @@ fn get_repo_changelog<P: AsRef<Path>>(
     Ok(text)
 }
 
+fn get_repo_previous_tag<P: AsRef<Path>>(path: P, tag: &str) -> Result<String> {
+    let mut cmd = Command::new("git");
+    cmd.arg("--git-dir")
+        .arg(path.as_ref())
+        .arg("describe")
+        .arg("--abbrev=0")
+        .arg("--tags")
+        .arg(format!("{}^", tag));
+
+    run_command_or(&mut cmd, "git describe failed")
+}
+
+fn get_repo_commits<P: AsRef<Path>>(
+    path: P,
+    prev_tag: &str,
+    tag: &str,
+) -> Result<String> {
+    let mut cmd = Command::new("git");
+    cmd.arg("--git-dir")
+        .arg(path.as_ref())
+        .arg("log")
+        .arg(format!("{}..{}", prev_tag, tag))
+        .arg("--no-merges")
+        .arg("--pretty=format:%H%x00%s%x00%b%x1e");
+
+    run_command_or(&mut cmd, "git log failed")
+}
+
+/// A parsed Conventional Commits subject line
+struct ConventionalCommit {
+    kind: String,
+    scope: Option<String>,
+    breaking: bool,
+    description: String,
+}
+
+fn parse_conventional_subject(subject: &str) -> Option<ConventionalCommit> {
+    let pattern =
+        Regex::new(r"^(\w+)(?:\(([^)]+)\))?(!)?:\s+(.+)$").ok()?;
+    let caps = pattern.captures(subject)?;
+    Some(ConventionalCommit {
+        kind: caps.get(1)?.as_str().to_lowercase(),
+        scope: caps.get(2).map(|m| m.as_str().to_string()),
+        breaking: caps.get(3).is_some(),
+        description: caps.get(4)?.as_str().to_string(),
+    })
+}
+
+fn format_entry(commit: &ConventionalCommit) -> String {
+    match &commit.scope {
+        Some(scope) => format!("- **{}:** {}", scope, commit.description),
+        None => format!("- {}", commit.description),
+    }
+}
+
+/// Synthesize a changelog section from Conventional Commits history
+fn generate_changelog_from_commits(log: &str) -> Result<String> {
+    let release_pattern = Regex::new(r"^chore\(release\)")?;
+    let breaking_footer = Regex::new(r"(?m)^BREAKING CHANGE:\s*(.+)$")?;
+    let mut features = Vec::new();
+    let mut fixes = Vec::new();
+    let mut others = Vec::new();
+    let mut breaking = Vec::new();
+
+    for record in log.split('\u{1e}') {
+        let record = record.trim();
+        if record.is_empty() {
+            continue;
+        }
+        let mut fields = record.splitn(4, '\u{0}');
+        let _hash = fields.next().unwrap_or_default();
+        let subject = fields.next().unwrap_or_default();
+        let body = fields.next().unwrap_or_default();
+        if release_pattern.is_match(subject) {
+            continue;
+        }
+        let commit = match parse_conventional_subject(subject) {
+            Some(commit) => commit,
+            None => continue,
+        };
+        let entry = format_entry(&commit);
+        if commit.breaking {
+            breaking.push(entry.clone());
+        } else if let Some(caps) = breaking_footer.captures(body) {
+            breaking.push(format!("- {}", &caps[1]));
+        }
+        match commit.kind.as_str() {
+            "feat" => features.push(entry),
+            "fix" => fixes.push(entry),
+            _ => others.push(entry),
+        }
+    }
+
+    let mut sections = Vec::new();
+    if !features.is_empty() {
+        sections.push(format!("### Features\n\n{}", features.join("\n")));
+    }
+    if !fixes.is_empty() {
+        sections.push(format!("### Bug Fixes\n\n{}", fixes.join("\n")));
+    }
+    if !others.is_empty() {
+        sections.push(format!("### Other\n\n{}", others.join("\n")));
+    }
+    if !breaking.is_empty() {
+        sections.push(format!("### BREAKING CHANGES\n\n{}", breaking.join("\n")));
+    }
+    let mut text = sections.join("\n\n");
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+fn get_git_changelog<P: AsRef<Path>>(path: P, tag: &str) -> Result<String> {
+    let prev_tag = get_repo_previous_tag(&path, tag)
+        .context("Failed to find previous tag")?;
+    let log = get_repo_commits(&path, &prev_tag, tag)
+        .context("Failed to read commits")?;
+    generate_changelog_from_commits(&log)
+}
+
 fn get_project_name(url: &str) -> Option<String> {
     let project = url.split('/').last()?;
     let name = match project.find(".git") {
@@ -200,6 +484,7 @@ struct Project {
     path: PathBuf,
     changelog: PathBuf,
     loose: bool,
+    from_git: bool,
 }
 
 impl Project {
@@ -209,6 +494,7 @@ impl Project {
             path: PathBuf::from(path.as_ref()),
             changelog: PathBuf::from("NEWS.md"),
             loose: false,
+            from_git: false,
         }
     }
 
@@ -227,9 +513,20 @@ impl Project {
         };
         let project = get_project_name(&url)
             .ok_or(anyhow!("Failed to extract project name from URL"))?;
-        let mut path = PathBuf::from(&self.path);
-        path.push(&self.changelog);
-        let changelog = get_repo_changelog(&path, sem_version)?;
+        let changelog = if self.from_git {
+            get_git_changelog(&gitdir, &version)
+                .context("Failed to generate changelog from git history")?
+        } else {
+            let mut path = PathBuf::from(&self.path);
+            path.push(&self.changelog);
+            let changelog = get_repo_changelog(&path, sem_version)?;
+            if changelog.trim().is_empty() {
+                get_git_changelog(&gitdir, &version)
+                    .context("Failed to generate changelog from git history")?
+            } else {
+                changelog
+            }
+        };
         let info = ReleaseInfo {
             project: project,
             url: url,
@@ -246,6 +543,10 @@ impl Project {
     fn set_loose(&mut self, loose: bool) {
         self.loose = loose;
     }
+
+    fn set_from_git(&mut self, from_git: bool) {
+        self.from_git = from_git;
+    }
 }
 
 /// Collect data to fill mail template
@@ -267,6 +568,11 @@ impl MailDataBuilder {
         self
     }
 
+    fn prefix(&mut self, prefix: &str) -> &mut Self {
+        self.data.insert("prefix".to_string(), prefix.to_string());
+        self
+    }
+
     fn recipients<S: AsRef<str>>(&mut self, recipients: &[S]) -> &mut Self {
         let recipients = recipients
             .iter()
@@ -277,6 +583,16 @@ impl MailDataBuilder {
         self
     }
 
+    fn bcc<S: AsRef<str>>(&mut self, bcc: &[S]) -> &mut Self {
+        let bcc = bcc
+            .iter()
+            .map(AsRef::as_ref)
+            .collect::<Vec<&str>>()
+            .join(", ");
+        self.data.insert("bcc".to_string(), bcc);
+        self
+    }
+
     fn info(&mut self, info: &ReleaseInfo) -> &mut Self {
         self.data
             .insert("project".to_string(), info.project.clone());
@@ -333,6 +649,378 @@ impl MailBuilder {
     }
 }
 
+/// A backend used to deliver a rendered announcement
+#[derive(Debug, Clone)]
+enum Transport {
+    /// Hand the message over to a local MTA
+    Sendmail { cmd: String },
+    /// Deliver the message over SMTP with STARTTLS, optionally with auth
+    Smtp {
+        host: String,
+        port: u16,
+        username: Option<String>,
+        password: Option<String>,
+    },
+}
+
+impl Transport {
+    /// Send `message` to `recipients` (plus `bcc`), using `emitter` as sender.
+    /// The sendmail backend ignores these three arguments: `sendmail -t`
+    /// takes the sender and recipients from the message headers instead.
+    fn send(
+        &self,
+        emitter: &str,
+        recipients: &[String],
+        bcc: &[String],
+        message: &str,
+    ) -> Result<()> {
+        match self {
+            Transport::Sendmail { cmd } => send_via_sendmail(cmd, message),
+            Transport::Smtp {
+                host,
+                port,
+                username,
+                password,
+            } => send_via_smtp(
+                host,
+                *port,
+                username.as_deref(),
+                password.as_deref(),
+                emitter,
+                recipients,
+                bcc,
+                message,
+            ),
+        }
+    }
+}
+
+fn send_via_sendmail(cmd: &str, message: &str) -> Result<()> {
+    let mut child = Command::new(cmd)
+        .arg("-t")
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("Failed to spawn sendmail")?;
+    {
+        let stdin = child
+            .stdin
+            .as_mut()
+            .ok_or(anyhow!("Failed to open sendmail stdin"))?;
+        stdin.write_all(message.as_bytes())?;
+    }
+    let status = child.wait().context("Failed to wait for sendmail")?;
+    if !status.success() {
+        return Err(anyhow!("sendmail exited with {}", status));
+    }
+    Ok(())
+}
+
+/// Drop any `bcc:` header line from a rendered message. Unlike `sendmail -t`,
+/// which consults `Bcc:` to build the envelope and then strips it itself,
+/// `send_raw` transmits the message bytes verbatim, so the SMTP path must
+/// remove the header itself or it would leak the bcc list to every recipient.
+fn strip_bcc_header(message: &str) -> String {
+    let (headers, body) = split_message(message);
+    let mut mail = String::new();
+    for (name, value) in headers {
+        if name.eq_ignore_ascii_case("bcc") {
+            continue;
+        }
+        mail.push_str(&format!("{}: {}\n", name, value));
+    }
+    mail.push('\n');
+    mail.push_str(&body);
+    mail
+}
+
+fn send_via_smtp(
+    host: &str,
+    port: u16,
+    username: Option<&str>,
+    password: Option<&str>,
+    emitter: &str,
+    recipients: &[String],
+    bcc: &[String],
+    message: &str,
+) -> Result<()> {
+    let message = strip_bcc_header(message);
+    let from = emitter.parse().context("Invalid emitter address")?;
+    let to = recipients
+        .iter()
+        .chain(bcc.iter())
+        .map(|r| r.parse().context("Invalid recipient address"))
+        .collect::<Result<Vec<_>>>()?;
+    let envelope = Envelope::new(Some(from), to)?;
+    let mut builder = SmtpTransport::starttls_relay(host)
+        .context("Failed to build SMTP transport")?
+        .port(port);
+    if let (Some(username), Some(password)) = (username, password) {
+        builder = builder.credentials(Credentials::new(
+            username.to_string(),
+            password.to_string(),
+        ));
+    }
+    let mailer = builder.build();
+    mailer
+        .send_raw(&envelope, message.as_bytes())
+        .context("Failed to send mail via SMTP")?;
+    Ok(())
+}
+
+fn get_smtp_password() -> Option<String> {
+    env::var("KEMENN_SMTP_PASSWORD").ok()
+}
+
+/// Wrap a base64 string onto RFC 2045 76-character lines
+fn wrap_base64(encoded: &str) -> String {
+    encoded
+        .as_bytes()
+        .chunks(76)
+        .map(|chunk| str::from_utf8(chunk).unwrap())
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+/// A file to be attached to an announcement
+#[derive(Debug, Clone)]
+struct Attachment {
+    path: PathBuf,
+}
+
+impl Attachment {
+    fn new(path: PathBuf) -> Self {
+        Attachment { path }
+    }
+
+    fn filename(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "attachment".to_string())
+    }
+
+    fn content_type(&self) -> String {
+        mime_guess::from_path(&self.path)
+            .first_or_octet_stream()
+            .to_string()
+    }
+
+    fn encode(&self) -> Result<String> {
+        let data = fs::read(&self.path).with_context(|| {
+            format!("Failed to read attachment {}", self.path.display())
+        })?;
+        Ok(wrap_base64(&base64::encode(&data)))
+    }
+}
+
+/// Encode a header value as an RFC 2047 encoded-word if it is not plain ASCII
+fn encode_header_value(value: &str) -> String {
+    if value.is_ascii() {
+        value.to_string()
+    } else {
+        format!("=?UTF-8?B?{}?=", base64::encode(value.as_bytes()))
+    }
+}
+
+/// Split a rendered message into its headers and its body
+fn split_message(message: &str) -> (Vec<(String, String)>, String) {
+    let mut lines = message.split('\n');
+    let mut headers = Vec::new();
+    for line in &mut lines {
+        if line.is_empty() {
+            break;
+        }
+        match line.find(':') {
+            Some(pos) => {
+                headers.push((
+                    line[..pos].trim().to_string(),
+                    line[pos + 1..].trim().to_string(),
+                ));
+            }
+            None => break,
+        }
+    }
+    let body = lines.collect::<Vec<&str>>().join("\n");
+    (headers, body)
+}
+
+/// Derive a MIME boundary that does not depend on randomness
+fn make_boundary(seed: &str) -> String {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    let mut hasher = DefaultHasher::new();
+    seed.hash(&mut hasher);
+    format!("=_kemenn_{:016x}", hasher.finish())
+}
+
+/// A single MIME entity: its own headers (`Content-Type` and friends) and body
+struct MimeEntity {
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl MimeEntity {
+    fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, value) in &self.headers {
+            out.push_str(&format!("{}: {}\n", name, value));
+        }
+        out.push('\n');
+        out.push_str(&self.body);
+        out
+    }
+}
+
+/// Build the MIME entity carrying the rendered body, wrapping it and any
+/// `attachments` into a `multipart/mixed` structure when attachments are given
+fn build_content_entity(
+    body: &str,
+    attachments: &[Attachment],
+) -> Result<MimeEntity> {
+    if attachments.is_empty() {
+        return Ok(MimeEntity {
+            headers: vec![
+                (
+                    "Content-Type".to_string(),
+                    "text/plain; charset=UTF-8".to_string(),
+                ),
+                (
+                    "Content-Transfer-Encoding".to_string(),
+                    "8bit".to_string(),
+                ),
+            ],
+            body: body.to_string(),
+        });
+    }
+    let boundary = make_boundary(body);
+    let mut parts = String::new();
+    parts.push_str(&format!("--{}\n", boundary));
+    parts.push_str("Content-Type: text/plain; charset=UTF-8\n");
+    parts.push_str("Content-Transfer-Encoding: 8bit\n\n");
+    parts.push_str(body);
+    parts.push('\n');
+    for attachment in attachments {
+        parts.push_str(&format!("--{}\n", boundary));
+        parts.push_str(&format!(
+            "Content-Type: {}; name=\"{}\"\n",
+            attachment.content_type(),
+            attachment.filename()
+        ));
+        parts.push_str("Content-Transfer-Encoding: base64\n");
+        parts.push_str(&format!(
+            "Content-Disposition: attachment; filename=\"{}\"\n\n",
+            attachment.filename()
+        ));
+        parts.push_str(&attachment.encode()?);
+        parts.push('\n');
+    }
+    parts.push_str(&format!("--{}--\n", boundary));
+    Ok(MimeEntity {
+        headers: vec![(
+            "Content-Type".to_string(),
+            format!("multipart/mixed; boundary=\"{}\"", boundary),
+        )],
+        body: parts,
+    })
+}
+
+/// Split a rendered message into its encoded RFC 5322 headers and its
+/// MIME content entity, wrapping the body and any `attachments` into a
+/// `multipart/mixed` structure when attachments are given
+fn build_mime_message(
+    message: &str,
+    attachments: &[Attachment],
+) -> Result<(Vec<(String, String)>, MimeEntity)> {
+    let (headers, body) = split_message(message);
+    let headers = headers
+        .into_iter()
+        .map(|(name, value)| (name, encode_header_value(&value)))
+        .collect();
+    let entity = build_content_entity(&body, attachments)?;
+    Ok((headers, entity))
+}
+
+/// Render the final RFC 5322 message from its headers and MIME entity
+fn render_message(headers: &[(String, String)], entity: &MimeEntity) -> String {
+    let mut mail = String::new();
+    for (name, value) in headers {
+        mail.push_str(&format!("{}: {}\n", name, value));
+    }
+    mail.push_str("MIME-Version: 1.0\n");
+    mail.push_str(&entity.render());
+    mail
+}
+
+/// Canonicalize line endings to CRLF, as required before signing
+fn canonicalize_crlf(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let text = text.strip_suffix('\n').unwrap_or(text);
+    for line in text.split('\n') {
+        out.push_str(line.strip_suffix('\r').unwrap_or(line));
+        out.push_str("\r\n");
+    }
+    out
+}
+
+fn gpg_detach_sign(data: &[u8], keyid: Option<&str>) -> Result<String> {
+    let mut cmd = Command::new("gpg");
+    cmd.arg("--detach-sign").arg("--armor");
+    if let Some(keyid) = keyid {
+        cmd.arg("--local-user").arg(keyid);
+    }
+    cmd.stdin(Stdio::piped()).stdout(Stdio::piped());
+    let mut child = cmd.spawn().context("Failed to spawn gpg")?;
+    {
+        let stdin = child
+            .stdin
+            .as_mut()
+            .ok_or(anyhow!("Failed to open gpg stdin"))?;
+        stdin.write_all(data)?;
+    }
+    let output = child.wait_with_output().context("Failed to run gpg")?;
+    if !output.status.success() {
+        return Err(anyhow!("gpg exited with {}", output.status));
+    }
+    String::from_utf8(output.stdout).context("gpg produced non-UTF-8 signature")
+}
+
+/// Wrap `canonical` (the CRLF-canonicalized signed part) and its detached
+/// ASCII-armored `signature` into a `multipart/signed` entity
+fn assemble_signed_entity(canonical: &str, signature: &str) -> MimeEntity {
+    let boundary = make_boundary(canonical);
+    let mut body = String::new();
+    body.push_str(&format!("--{}\r\n", boundary));
+    body.push_str(canonical);
+    body.push_str("\r\n--");
+    body.push_str(&boundary);
+    body.push_str("\r\n");
+    body.push_str(
+        "Content-Type: application/pgp-signature; name=\"signature.asc\"\r\n",
+    );
+    body.push_str("Content-Description: OpenPGP digital signature\r\n\r\n");
+    body.push_str(signature);
+    body.push_str(&format!("--{}--\r\n", boundary));
+    MimeEntity {
+        headers: vec![(
+            "Content-Type".to_string(),
+            format!(
+                "multipart/signed; micalg=\"pgp-sha256\"; protocol=\"application/pgp-signature\"; boundary=\"{}\"",
+                boundary
+            ),
+        )],
+        body,
+    }
+}
+
+/// Wrap `entity` into a `multipart/signed` structure, detached-signing its
+/// CRLF-canonicalized bytes with gpg
+fn sign_entity(entity: MimeEntity, keyid: Option<&str>) -> Result<MimeEntity> {
+    let canonical = canonicalize_crlf(&entity.render());
+    let signature = gpg_detach_sign(canonical.as_bytes(), keyid)
+        .context("Failed to sign announcement with gpg")?;
+    Ok(assemble_signed_entity(&canonical, &signature))
+}
+
 fn get_logged_user_email() -> Option<String> {
     let username = env::var("USER").or(env::var("USERNAME")).ok()?;
     env::var("HOSTNAME")
@@ -373,7 +1061,10 @@ fn add_recipients_from_path<P: AsRef<Path>>(
     Ok(())
 }
 
-fn get_signature() -> Option<String> {
+fn get_signature(path: Option<&Path>) -> Option<String> {
+    if let Some(path) = path {
+        return fs::read_to_string(path).ok();
+    }
     if let Some(mut path) = dirs::home_dir() {
         path.push(".signature");
         let text = fs::read_to_string(&path).ok()?;
@@ -384,29 +1075,52 @@ fn get_signature() -> Option<String> {
 
 fn main() -> Result<()> {
     let mut opts = KemennOpts::from_args();
+    let config_path = opts.config.clone().or_else(Config::default_path);
+    let profile = match &config_path {
+        Some(path) if path.exists() => Config::load(path)
+            .context("Failed to load config file")?
+            .resolve(opts.profile.as_deref())?,
+        _ => match &opts.profile {
+            Some(name) => return Err(anyhow!("Unknown profile '{}'", name)),
+            None => ProfileConfig::default(),
+        },
+    };
     let emitter = opts
         .emitter
+        .clone()
+        .or_else(|| profile.emitter.clone())
         .or_else(get_user_email)
         .ok_or(anyhow!("Missing emitter email"))?;
     if let Some(input) = opts.input {
         add_recipients_from_path(&mut opts.recipients, input)
             .context("Failed to add recipients from input")?;
     }
+    if opts.recipients.is_empty() {
+        if let Some(recipients) = &profile.recipients {
+            opts.recipients = recipients.clone();
+        }
+    }
     let mut project = Project::new(&opts.repository);
     if let Some(changelog) = opts.changelog {
         project.set_changelog(&changelog);
     }
     project.set_loose(opts.loose);
+    project.set_from_git(opts.from_git);
 
     let info = project
         .release_info(&opts.release)
         .context("Failed to get release info")?;
+    let bcc = profile.bcc.clone().unwrap_or_else(|| vec![emitter.clone()]);
     let mut builder = MailDataBuilder::new();
     builder
         .emitter(&emitter)
         .recipients(&opts.recipients)
+        .bcc(&bcc)
         .info(&info);
-    if let Some(signature) = get_signature() {
+    if let Some(prefix) = &profile.prefix {
+        builder.prefix(prefix);
+    }
+    if let Some(signature) = get_signature(profile.signature.as_deref()) {
         builder.signature(&signature);
     }
     if let Some(parameters) = opts.parameters {
@@ -418,13 +1132,60 @@ fn main() -> Result<()> {
     }
     let data = builder.build();
     let mut builder = MailBuilder::new();
-    if let Some(template) = opts.template {
+    let template = opts.template.or_else(|| profile.template.clone());
+    if let Some(template) = template {
         let text =
             fs::read_to_string(template).context("Failed to read template")?;
         builder.template(&text);
     }
     let text = builder.build(&data)?;
-    if let Some(output) = opts.output {
+    let attachments: Vec<Attachment> = opts
+        .attachments
+        .iter()
+        .cloned()
+        .map(Attachment::new)
+        .collect();
+    let (headers, entity) = build_mime_message(&text, &attachments)
+        .context("Failed to assemble MIME message")?;
+    let entity = match &opts.sign {
+        Some(values) => {
+            let keyid = values.first().map(String::as_str);
+            sign_entity(entity, keyid).context("Failed to sign announcement")?
+        }
+        None => entity,
+    };
+    let text = render_message(&headers, &entity);
+    if opts.send {
+        let transport_cfg = profile.transport.clone().unwrap_or_default();
+        let smtp_host = opts.smtp_host.or(transport_cfg.smtp_host);
+        let transport = match smtp_host {
+            Some(host) => {
+                let username =
+                    opts.smtp_user.clone().or(transport_cfg.smtp_user);
+                let password = username
+                    .as_ref()
+                    .and(get_smtp_password().or(transport_cfg.smtp_password));
+                Transport::Smtp {
+                    host,
+                    port: opts
+                        .smtp_port
+                        .or(transport_cfg.smtp_port)
+                        .unwrap_or(DEFAULT_SMTP_PORT),
+                    username,
+                    password,
+                }
+            }
+            None => Transport::Sendmail {
+                cmd: opts
+                    .sendmail_cmd
+                    .or(transport_cfg.sendmail_cmd)
+                    .unwrap_or_else(|| DEFAULT_SENDMAIL_CMD.to_string()),
+            },
+        };
+        transport
+            .send(&emitter, &opts.recipients, &bcc, &text)
+            .context("Failed to send announcement")?;
+    } else if let Some(output) = opts.output {
         fs::write(output, text).context("Failed to write output")?;
     } else {
         let stdout = io::stdout();
@@ -433,3 +1194,110 @@ fn main() -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_header_value_passes_through_ascii() {
+        assert_eq!(encode_header_value("hello"), "hello");
+    }
+
+    #[test]
+    fn encode_header_value_base64_encodes_non_ascii() {
+        let encoded = encode_header_value("Éric Le Bihan");
+        assert!(encoded.starts_with("=?UTF-8?B?"));
+        assert!(encoded.ends_with("?="));
+    }
+
+    #[test]
+    fn split_message_separates_headers_and_body() {
+        let message = "From: a@b\nTo: c@d\n\nHello\nWorld\n";
+        let (headers, body) = split_message(message);
+        assert_eq!(
+            headers,
+            vec![
+                ("From".to_string(), "a@b".to_string()),
+                ("To".to_string(), "c@d".to_string()),
+            ]
+        );
+        assert_eq!(body, "Hello\nWorld\n");
+    }
+
+    #[test]
+    fn make_boundary_is_deterministic_and_seed_dependent() {
+        assert_eq!(make_boundary("seed"), make_boundary("seed"));
+        assert_ne!(make_boundary("seed-a"), make_boundary("seed-b"));
+    }
+
+    #[test]
+    fn build_content_entity_without_attachments_is_plain_text() {
+        let entity = build_content_entity("Hello!", &[]).unwrap();
+        assert_eq!(
+            entity.headers,
+            vec![
+                (
+                    "Content-Type".to_string(),
+                    "text/plain; charset=UTF-8".to_string()
+                ),
+                (
+                    "Content-Transfer-Encoding".to_string(),
+                    "8bit".to_string()
+                ),
+            ]
+        );
+        assert_eq!(entity.body, "Hello!");
+    }
+
+    #[test]
+    fn build_content_entity_with_attachment_is_multipart_mixed() {
+        let mut path = env::temp_dir();
+        path.push(format!("kemenn-test-{}.txt", std::process::id()));
+        fs::write(&path, b"payload").unwrap();
+        let attachment = Attachment::new(path.clone());
+
+        let entity = build_content_entity("Hello!", &[attachment]).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(entity.headers.len(), 1);
+        assert!(entity.headers[0]
+            .1
+            .starts_with("multipart/mixed; boundary="));
+        assert!(entity.body.contains("Hello!"));
+        assert!(entity.body.contains("Content-Disposition: attachment"));
+        assert!(entity.body.contains(&base64::encode(b"payload")));
+    }
+
+    #[test]
+    fn canonicalize_crlf_normalizes_mixed_line_endings() {
+        let text = "a\r\nb\nc";
+        assert_eq!(canonicalize_crlf(text), "a\r\nb\r\nc\r\n");
+    }
+
+    #[test]
+    fn assemble_signed_entity_wraps_canonical_and_signature() {
+        let canonical = "From: a@b\r\n\r\nHello\r\n";
+        let signature =
+            "-----BEGIN PGP SIGNATURE-----\r\nabc\r\n-----END PGP SIGNATURE-----\r\n";
+
+        let entity = assemble_signed_entity(canonical, signature);
+
+        assert_eq!(entity.headers.len(), 1);
+        let (name, value) = &entity.headers[0];
+        assert_eq!(name, "Content-Type");
+        assert!(value.starts_with("multipart/signed;"));
+        assert!(value.contains("protocol=\"application/pgp-signature\""));
+
+        let boundary = value
+            .split("boundary=\"")
+            .nth(1)
+            .and_then(|s| s.strip_suffix('"'))
+            .expect("boundary parameter");
+        let delimiter = format!("--{}\r\n", boundary);
+        assert_eq!(entity.body.matches(delimiter.as_str()).count(), 2);
+        assert!(entity.body.ends_with(&format!("--{}--\r\n", boundary)));
+        assert!(entity.body.contains(canonical));
+        assert!(entity.body.contains(signature));
+    }
+}